@@ -0,0 +1,182 @@
+use std::{
+    collections::HashMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    body::{to_bytes, Body},
+    extract::{Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::Response,
+};
+use base64::Engine;
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use serde_json::Value;
+use tracing::warn;
+
+use crate::AppState;
+
+/// How far a request's `X-Timestamp` may drift from "now" before it's rejected as stale/replayed.
+const MAX_CLOCK_SKEW_SECS: u64 = 60;
+
+/// The set of ed25519 public keys (by key id) authorized to sign mutating requests, analogous
+/// to Matrix's server-to-server `X-Matrix` key registry.
+#[derive(Clone, Default)]
+pub struct AuthorizedKeys(HashMap<String, VerifyingKey>);
+
+impl AuthorizedKeys {
+    pub fn from_hex_map(keys: &HashMap<String, String>) -> anyhow::Result<Self> {
+        let mut parsed = HashMap::new();
+        for (key_id, hex_key) in keys {
+            let bytes: [u8; 32] = hex::decode(hex_key)?
+                .try_into()
+                .map_err(|_| anyhow::anyhow!("public key for {key_id} must be 32 bytes"))?;
+            parsed.insert(key_id.clone(), VerifyingKey::from_bytes(&bytes)?);
+        }
+        Ok(Self(parsed))
+    }
+
+    fn get(&self, key_id: &str) -> Option<&VerifyingKey> {
+        self.0.get(key_id)
+    }
+}
+
+/// Recursively sorts JSON object keys so both client and server hash the same bytes regardless
+/// of field order in the original request.
+fn canonicalize(value: &Value) -> Value {
+    match value {
+        Value::Object(map) => {
+            let mut keys: Vec<&String> = map.keys().collect();
+            keys.sort();
+            let sorted = keys
+                .into_iter()
+                .map(|k| (k.clone(), canonicalize(&map[k])))
+                .collect();
+            Value::Object(sorted)
+        }
+        Value::Array(items) => Value::Array(items.iter().map(canonicalize).collect()),
+        other => other.clone(),
+    }
+}
+
+/// Builds the string a caller signs: method, path, timestamp, and the body re-serialized with
+/// sorted object keys. Shared by the client-side signer and this server-side verifier so both
+/// hash identical bytes.
+pub fn canonical_request(
+    method: &str,
+    path: &str,
+    timestamp: &str,
+    body: &[u8],
+) -> anyhow::Result<String> {
+    let canonical_body = if body.is_empty() {
+        String::new()
+    } else {
+        let value: Value = serde_json::from_slice(body)?;
+        serde_json::to_string(&canonicalize(&value))?
+    };
+
+    Ok(format!("{method}\n{path}\n{timestamp}\n{canonical_body}"))
+}
+
+/// Axum middleware enforcing X-Matrix-style request signing on mutating routes. The caller
+/// signs `canonical_request(...)` with ed25519 and presents the result as
+/// `X-Signature: <key_id>:<base64 signature>` alongside an `X-Timestamp` unix-seconds header.
+pub async fn verify_signature(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    let (parts, body) = request.into_parts();
+
+    let timestamp = parts
+        .headers
+        .get("X-Timestamp")
+        .and_then(|v| v.to_str().ok())
+        .ok_or(StatusCode::UNAUTHORIZED)?
+        .to_string();
+
+    let claimed_ts: u64 = timestamp.parse().map_err(|_| StatusCode::UNAUTHORIZED)?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    if now.abs_diff(claimed_ts) > MAX_CLOCK_SKEW_SECS {
+        warn!(claimed_ts, now, "rejected request with stale timestamp");
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let (key_id, signature_b64) = parts
+        .headers
+        .get("X-Signature")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split_once(':'))
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+
+    let verifying_key = state
+        .authorized_keys
+        .get(key_id)
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+
+    let signature_bytes = base64::engine::general_purpose::STANDARD
+        .decode(signature_b64)
+        .map_err(|_| StatusCode::UNAUTHORIZED)?;
+    let signature =
+        Signature::from_slice(&signature_bytes).map_err(|_| StatusCode::UNAUTHORIZED)?;
+
+    let body_bytes = to_bytes(body, usize::MAX)
+        .await
+        .map_err(|_| StatusCode::BAD_REQUEST)?;
+    let canonical = canonical_request(parts.method.as_str(), parts.uri.path(), &timestamp, &body_bytes)
+        .map_err(|_| StatusCode::BAD_REQUEST)?;
+
+    verifying_key
+        .verify(canonical.as_bytes(), &signature)
+        .map_err(|_| {
+            warn!(key_id, "rejected request with invalid signature");
+            StatusCode::UNAUTHORIZED
+        })?;
+
+    let request = Request::from_parts(parts, Body::from(body_bytes));
+    Ok(next.run(request).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn canonical_request_is_independent_of_key_order() {
+        let a = canonical_request("PUT", "/events", "100", br#"{"host":1,"attendance":[2,3]}"#)
+            .unwrap();
+        let b = canonical_request("PUT", "/events", "100", br#"{"attendance":[2,3],"host":1}"#)
+            .unwrap();
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn canonical_request_sorts_nested_object_keys_too() {
+        let a = canonical_request(
+            "POST",
+            "/profiles/create",
+            "100",
+            br#"{"outer":{"b":1,"a":2}}"#,
+        )
+        .unwrap();
+        let b = canonical_request(
+            "POST",
+            "/profiles/create",
+            "100",
+            br#"{"outer":{"a":2,"b":1}}"#,
+        )
+        .unwrap();
+        assert_eq!(a, b);
+        assert!(a.ends_with(r#"{"outer":{"a":2,"b":1}}"#));
+    }
+
+    #[test]
+    fn canonical_request_leaves_body_segment_empty_for_no_body() {
+        let canonical = canonical_request("PUT", "/appservices", "100", b"").unwrap();
+        assert_eq!(canonical, "PUT\n/appservices\n100\n");
+    }
+}
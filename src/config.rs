@@ -0,0 +1,146 @@
+use std::collections::HashMap;
+
+use anyhow::{bail, Result};
+use toml::Table;
+
+use crate::util;
+
+/// Policy and feature toggles loaded from `Secrets.toml`, following the pattern of Conduit's
+/// `conduit.toml`. Lets the same binary serve different groups/rule sets without recompiling
+/// the mark/promotion logic.
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub bind_address: String,
+    pub port: u16,
+    /// Whether `get_profile` silently creates a profile for a newly-seen SOL member instead of
+    /// returning `None`.
+    pub allow_profile_autocreate: bool,
+    /// Events a member must attend in a calendar week before a mark is awarded.
+    pub events_per_week_for_mark: i32,
+    /// Per-rank overrides of marks required to promote, keyed by Roblox group rank id. Ranks
+    /// not present here fall back to `Rank::required_marks`.
+    pub required_marks: HashMap<u64, i32>,
+    /// Maximum attendee count accepted for a single hosted event.
+    pub max_attendance: usize,
+}
+
+impl Config {
+    pub fn from_secrets(secrets: &Table) -> Result<Self> {
+        let bind_address = secrets
+            .get("BIND_ADDRESS")
+            .map(|v| util::strip_token(v.to_string()))
+            .unwrap_or_else(|| "0.0.0.0".to_string());
+
+        let port = secrets
+            .get("PORT")
+            .map(|v| util::strip_token(v.to_string()))
+            .map(|s| s.parse::<u16>())
+            .transpose()?
+            .unwrap_or(3000);
+
+        let allow_profile_autocreate = secrets
+            .get("ALLOW_PROFILE_AUTOCREATE")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
+
+        let events_per_week_for_mark = secrets
+            .get("EVENTS_PER_WEEK_FOR_MARK")
+            .and_then(|v| v.as_integer())
+            .map(|v| v as i32)
+            .unwrap_or(sol_util::rank::EVENT_PER_WEEK_FOR_MARK);
+
+        let required_marks = secrets
+            .get("REQUIRED_MARKS")
+            .and_then(|v| v.as_table())
+            .map(|table| {
+                table
+                    .iter()
+                    .filter_map(|(rank_id, marks)| {
+                        Some((rank_id.parse::<u64>().ok()?, marks.as_integer()? as i32))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let max_attendance = secrets
+            .get("MAX_ATTENDANCE")
+            .and_then(|v| v.as_integer())
+            .map(|v| v as usize)
+            .unwrap_or(100);
+
+        let config = Self {
+            bind_address,
+            port,
+            allow_profile_autocreate,
+            events_per_week_for_mark,
+            required_marks,
+            max_attendance,
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.port == 0 {
+            bail!("PORT must be nonzero");
+        }
+        if self.events_per_week_for_mark <= 0 {
+            bail!("EVENTS_PER_WEEK_FOR_MARK must be positive");
+        }
+        if self.max_attendance == 0 {
+            bail!("MAX_ATTENDANCE must be positive");
+        }
+        Ok(())
+    }
+
+    /// Marks required to promote out of `rank_id`, preferring a configured override over the
+    /// hardcoded `Rank::required_marks` table.
+    pub fn required_marks_for(&self, rank_id: u64) -> Option<i32> {
+        if let Some(marks) = self.required_marks.get(&rank_id) {
+            return Some(*marks);
+        }
+
+        sol_util::rank::Rank::from_rank_id(rank_id).and_then(|rank| rank.required_marks())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secrets(toml: &str) -> Table {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn from_secrets_rejects_zero_port() {
+        let err = Config::from_secrets(&secrets("PORT = 0")).unwrap_err();
+        assert!(err.to_string().contains("PORT"));
+    }
+
+    #[test]
+    fn from_secrets_rejects_nonpositive_events_per_week_for_mark() {
+        let err =
+            Config::from_secrets(&secrets("EVENTS_PER_WEEK_FOR_MARK = 0")).unwrap_err();
+        assert!(err.to_string().contains("EVENTS_PER_WEEK_FOR_MARK"));
+    }
+
+    #[test]
+    fn from_secrets_rejects_zero_max_attendance() {
+        let err = Config::from_secrets(&secrets("MAX_ATTENDANCE = 0")).unwrap_err();
+        assert!(err.to_string().contains("MAX_ATTENDANCE"));
+    }
+
+    #[test]
+    fn from_secrets_accepts_defaults_with_empty_table() {
+        let config = Config::from_secrets(&secrets("")).unwrap();
+        assert_eq!(config.port, 3000);
+        assert_eq!(config.max_attendance, 100);
+    }
+
+    #[test]
+    fn required_marks_for_prefers_override_over_fallback() {
+        let config = Config::from_secrets(&secrets("[REQUIRED_MARKS]\n1 = 7")).unwrap();
+        assert_eq!(config.required_marks_for(1), Some(7));
+    }
+}
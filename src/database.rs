@@ -0,0 +1,192 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use chrono::Utc;
+use libsql::{Builder, Connection, Database as LibsqlDatabase};
+use sol_util::mainframe::{Event, Profile};
+use tracing::instrument;
+
+/// Creates/upgrades the `profiles` and `events` schemas, plus the feature tables added
+/// alongside them, deterministically at startup rather than assuming they pre-exist.
+const MIGRATIONS: &str = "
+CREATE TABLE IF NOT EXISTS profiles (
+    user_id INTEGER PRIMARY KEY,
+    username TEXT,
+    rank_id INTEGER NOT NULL,
+    last_event_attended_date TEXT,
+    total_marks INTEGER NOT NULL DEFAULT 0,
+    marks_at_current_rank INTEGER NOT NULL DEFAULT 0,
+    events_attended_this_week INTEGER NOT NULL DEFAULT 0
+);
+CREATE TABLE IF NOT EXISTS events (
+    event_id INTEGER PRIMARY KEY AUTOINCREMENT,
+    host INTEGER NOT NULL,
+    attendance TEXT NOT NULL,
+    event_date TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    location TEXT NOT NULL,
+    metadata TEXT
+);
+CREATE TABLE IF NOT EXISTS appservices (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    url TEXT NOT NULL,
+    hs_token TEXT NOT NULL,
+    namespaces TEXT NOT NULL,
+    consecutive_failures INTEGER NOT NULL DEFAULT 0
+);
+CREATE TABLE IF NOT EXISTS transactions (
+    caller TEXT NOT NULL,
+    txn_id TEXT NOT NULL,
+    result TEXT NOT NULL,
+    PRIMARY KEY (caller, txn_id)
+);
+CREATE TABLE IF NOT EXISTS presence (
+    user_id INTEGER PRIMARY KEY,
+    last_online TEXT,
+    status TEXT NOT NULL
+);
+";
+
+/// Shared, cloneable handle to the mainframe's libsql connection, created once at startup so
+/// handlers stop paying the cost of `Builder::new_remote(...).build()` on every request.
+#[derive(Clone)]
+pub struct Database {
+    inner: Arc<LibsqlDatabase>,
+}
+
+impl Database {
+    #[instrument(skip(token))]
+    pub async fn connect(url: String, token: String) -> Result<Self> {
+        let inner = Builder::new_remote(url, token).build().await?;
+        Ok(Self {
+            inner: Arc::new(inner),
+        })
+    }
+
+    /// Opens a logical connection against the shared, already-established client. Cheap
+    /// relative to the old per-request remote build.
+    pub fn conn(&self) -> Connection {
+        self.inner
+            .connect()
+            .expect("failed to open connection from pool")
+    }
+
+    #[instrument(skip(self))]
+    pub async fn run_migrations(&self) -> Result<()> {
+        self.conn().execute_batch(MIGRATIONS).await?;
+        Ok(())
+    }
+
+    pub async fn get_profile(&self, user_id: u64, rank_id: u64) -> Result<(Profile, bool)> {
+        let conn = self.conn();
+        let mut rows = conn
+            .query("SELECT * FROM profiles WHERE user_id = ?1", [user_id])
+            .await?;
+
+        match rows.next().await? {
+            Some(row) => Ok((Profile::from_row(&row), true)),
+            None => Ok((Profile::new(user_id, None, rank_id), false)),
+        }
+    }
+
+    async fn events_attended(conn: &Connection, user_id: u64) -> Result<Vec<u64>> {
+        let mut rows = conn
+            .query("SELECT event_id, attendance FROM events", ())
+            .await?;
+
+        let mut attended = Vec::new();
+        while let Some(row) = rows.next().await? {
+            let event_id = row.get::<u64>(0)?;
+            let attendance: Vec<u64> = serde_json::from_str(&row.get::<String>(1)?)?;
+            if attendance.contains(&user_id) {
+                attended.push(event_id);
+            }
+        }
+
+        Ok(attended)
+    }
+
+    pub async fn get_attended(&self, user_id: u64) -> Result<u64> {
+        let conn = self.conn();
+        Ok(Self::events_attended(&conn, user_id).await?.len() as u64)
+    }
+
+    pub async fn get_events_attended(&self, user_id: u64) -> Result<Vec<u64>> {
+        let conn = self.conn();
+        Self::events_attended(&conn, user_id).await
+    }
+
+    pub async fn get_event(&self, event_id: i32) -> Result<Option<Event>> {
+        let conn = self.conn();
+        let mut rows = conn
+            .query("SELECT * FROM events WHERE event_id = ?1", [event_id])
+            .await?;
+
+        Ok(rows.next().await?.map(|row| Event::from_row(&row)))
+    }
+
+    /// Applies a weekly event-attendance increment to `user_id`'s profile: rolls over
+    /// `events_attended_this_week` if the week has turned over, adds `increment`, and awards a
+    /// mark via `Profile::try_award_mark` if `events_per_week_for_mark` is hit. Returns `None` if
+    /// no profile exists for `user_id`.
+    pub async fn increment_events(
+        &self,
+        user_id: u64,
+        increment: i32,
+        events_per_week_for_mark: i32,
+    ) -> Result<Option<Profile>> {
+        let conn = self.conn();
+        let mut rows = conn
+            .query("SELECT * FROM profiles WHERE user_id = ?1", [user_id])
+            .await?;
+
+        let Some(row) = rows.next().await? else {
+            return Ok(None);
+        };
+
+        let mut profile = Profile::from_row(&row);
+        profile.try_reset_events();
+        profile.events_attended_this_week += increment;
+        profile.last_event_attended_date = Some(Utc::now());
+        profile.try_award_mark(events_per_week_for_mark);
+
+        let last_event_attended_date_str = profile
+            .last_event_attended_date
+            .map(|d| d.to_rfc3339())
+            .unwrap_or_else(|| "null".to_string());
+
+        conn.execute(
+            "UPDATE profiles SET last_event_attended_date = ?2, total_marks = ?3, marks_at_current_rank = ?4, events_attended_this_week = ?5 WHERE user_id = ?1",
+            (
+                profile.user_id,
+                last_event_attended_date_str,
+                profile.total_marks,
+                profile.marks_at_current_rank,
+                profile.events_attended_this_week,
+            ),
+        )
+        .await?;
+
+        Ok(Some(profile))
+    }
+
+    /// Inserts a new profile with caller-supplied starting counters, for bot-side backfills
+    /// where `events`/`marks` are already known (unlike `get_profile`'s zeroed autocreate).
+    pub async fn create_profile(
+        &self,
+        user_id: u64,
+        username: String,
+        rank_id: u64,
+        events: i32,
+        marks: i32,
+    ) -> Result<()> {
+        self.conn()
+            .execute(
+                "INSERT INTO profiles (user_id, username, rank_id, last_event_attended_date, total_marks, marks_at_current_rank, events_attended_this_week) VALUES (?1, ?2, ?3, 'null', ?4, ?4, ?5)",
+                (user_id, username, rank_id, marks, events),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
@@ -1,115 +1,123 @@
 use axum::{
     extract::{Path, State},
     http::StatusCode,
-    routing::{get, put},
+    routing::{get, post, put},
     Json, Router,
 };
 
 use event::Attendance;
-use libsql::Builder;
-use sol_util::mainframe::{Event, EventJsonBody, Profile};
+use libsql::Connection;
+use opentelemetry::global;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use serde::{Deserialize, Serialize};
+use sol_util::mainframe::{CreateProfileBody, Event, EventJsonBody, Presence, PresenceStatus, Profile};
 use toml::Table;
+use tower_http::trace::TraceLayer;
+use tracing::{info, instrument, warn};
 
-use std::{fs, sync::Arc};
+use std::{fs, sync::Arc, time::Duration};
 
+mod auth;
+mod config;
 mod database;
 mod event;
 mod roblox;
+mod telemetry;
 mod util;
+mod webhooks;
 
 #[derive(Clone)]
 struct AppState {
-    token: String,
-    url: String,
+    db: database::Database,
+    authorized_keys: auth::AuthorizedKeys,
+    config: Arc<config::Config>,
 }
 
+#[instrument(skip(state))]
 async fn get_profile(
     State(state): State<AppState>,
     Path(user_id): Path<u64>,
 ) -> Json<Option<Profile>> {
-    println!("Retrieving profile for {user_id}");
-    let db = Builder::new_remote(state.url, state.token)
-        .build()
-        .await
-        .unwrap();
-    let conn = db.connect().unwrap();
+    info!(user_id, "retrieving profile");
 
     let sol_rank_id = match roblox::get_rank_in_group(roblox::SOL_GROUP_ID, user_id).await {
         Ok(None) => {
-            println!("Profile {user_id} retrieval failed, not in SOL");
+            warn!(user_id, "profile retrieval failed, not in SOL");
             return Json(None);
         }
         Ok(Some((id, _))) => id,
         Err(e) => panic!("{}", e.to_string()),
     };
 
-    let (profile, in_db) = database::get_profile(user_id, sol_rank_id, &conn).await;
+    let (profile, in_db) = state.db.get_profile(user_id, sol_rank_id).await.unwrap();
     if in_db {
-        println!("Retrieved {profile:?}");
+        info!(user_id, rank_id = sol_rank_id, "retrieved profile");
+        return Json(Some(profile));
+    }
+
+    if state.config.allow_profile_autocreate {
+        let profile = Profile::new(user_id, None, sol_rank_id);
+        state.db.conn().execute(
+            "INSERT INTO profiles (user_id, username, rank_id, last_event_attended_date, total_marks, marks_at_current_rank, events_attended_this_week) VALUES (?1, ?2, ?3, 'null', 0, 0, 0)",
+            (profile.user_id, profile.username.clone(), profile.rank_id),
+        )
+        .await
+        .unwrap();
+
+        info!(user_id, rank_id = sol_rank_id, "autocreated profile");
         return Json(Some(profile));
     }
 
-    println!("Profile {user_id} retrieval failed, no profile found");
+    warn!(user_id, "profile retrieval failed, no profile found");
     Json(None)
 }
 
+#[instrument(skip(state))]
 async fn get_attended(State(state): State<AppState>, Path(user_id): Path<u64>) -> Json<u64> {
-    println!("Counting events attended for {user_id}");
-    let db = Builder::new_remote(state.url, state.token)
-        .build()
-        .await
-        .unwrap();
-    let conn = db.connect().unwrap();
-
-    let count = database::get_attended(user_id, conn).await;
-    println!("{user_id} has attended {count} events");
+    info!(user_id, "counting events attended");
+    let count = state.db.get_attended(user_id).await.unwrap();
+    info!(user_id, count, "counted events attended");
     Json(count)
 }
 
+#[instrument(skip(state))]
 async fn get_events_attended(
     State(state): State<AppState>,
     Path(user_id): Path<u64>,
 ) -> Json<Vec<u64>> {
-    println!("Retrieving event ids for user {user_id}");
-    let db = Builder::new_remote(state.url, state.token)
-        .build()
-        .await
-        .unwrap();
-    let conn = db.connect().unwrap();
-
-    let events = database::get_events_attended(user_id, conn).await;
-    println!("{user_id} has attended {events:?}");
+    info!(user_id, "retrieving event ids");
+    let events = state.db.get_events_attended(user_id).await.unwrap();
+    info!(user_id, count = events.len(), "retrieved event ids");
     Json(events)
 }
 
+#[instrument(skip(state))]
 async fn get_event_info_by_info(
     State(state): State<AppState>,
     Path(event_id): Path<i32>,
 ) -> Json<Option<Event>> {
-    println!("Getting event {event_id}");
-    let db = Builder::new_remote(state.url, state.token)
-        .build()
-        .await
-        .unwrap();
-    let conn = db.connect().unwrap();
-
-    let event = database::get_event(event_id, conn).await.unwrap_or(None);
-    println!("Got event {event:?}");
+    info!(event_id, "getting event");
+    let event = state.db.get_event(event_id).await.unwrap_or(None);
+    info!(event_id, found = event.is_some(), "got event");
     Json(event)
 }
 
+#[instrument(skip(state, body), fields(host = body.host, location = %body.location, kind = %body.kind))]
 async fn put_event(State(state): State<AppState>, Json(body): Json<EventJsonBody>) -> StatusCode {
-    println!(
-        "Processing event hosted by {} at {}",
-        body.host, body.location
-    );
-    let db = Builder::new_remote(state.url, state.token)
-        .build()
-        .await
-        .unwrap();
-    let conn = db.connect().unwrap();
+    info!("processing event");
 
     let event = Event::from_json_body(body);
+    if event.attendance.len() > state.config.max_attendance {
+        warn!(
+            host = event.host,
+            attendance = event.attendance.len(),
+            max_attendance = state.config.max_attendance,
+            "rejected event, attendance exceeds max_attendance"
+        );
+        return StatusCode::PAYLOAD_TOO_LARGE;
+    }
+
+    let conn = state.db.conn();
 
     let attendance_string = serde_json::to_string(&event.attendance).unwrap();
     conn.execute("INSERT INTO events (host, attendance, event_date, kind, location) VALUES (?1, ?2, ?3, ?4, ?5)", (
@@ -121,20 +129,326 @@ async fn put_event(State(state): State<AppState>, Json(body): Json<EventJsonBody
     )).await.unwrap();
 
     let conn_arc = Arc::new(conn);
-    event.log_attendance(conn_arc).await;
+    event.log_attendance(conn_arc.clone()).await;
 
-    println!("Logged {event:?}");
+    let host = event.host;
+    let attendance = event.attendance.clone();
+    webhooks::notify_event(conn_arc.clone(), event);
+    webhooks::check_and_notify_promotions(conn_arc, attendance, state.config.clone()).await;
+
+    info!(host, "logged event");
     StatusCode::OK
 }
 
-// gets the hosted events from the specified userid
-async fn get_hosted(State(state): State<AppState>, Path(host_id): Path<u64>) -> Json<Vec<Event>> {
-    println!("Retrieving events hosted by {host_id}");
-    let db = Builder::new_remote(state.url, state.token)
-        .build()
+#[instrument(skip(state, body))]
+async fn register_webhook(
+    State(state): State<AppState>,
+    Json(body): Json<webhooks::RegisterWebhookBody>,
+) -> Result<Json<i64>, StatusCode> {
+    let conn = state.db.conn();
+
+    webhooks::register(&conn, body).await.map(Json).map_err(|e| {
+        warn!(error = %e, "failed to register webhook");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+#[instrument(skip(state))]
+async fn increment_events(
+    State(state): State<AppState>,
+    Path((user_id, increment)): Path<(u64, i32)>,
+) -> StatusCode {
+    match state
+        .db
+        .increment_events(user_id, increment, state.config.events_per_week_for_mark)
+        .await
+    {
+        Ok(Some(_)) => StatusCode::OK,
+        Ok(None) => {
+            warn!(user_id, "increment_events failed, no profile found");
+            StatusCode::NOT_FOUND
+        }
+        Err(e) => {
+            warn!(user_id, error = %e, "failed to increment events");
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+#[instrument(skip(state, body), fields(user_id = body.user_id, rank_id = body.rank_id))]
+async fn create_profile(State(state): State<AppState>, Json(body): Json<CreateProfileBody>) -> StatusCode {
+    match state
+        .db
+        .create_profile(body.user_id, body.username, body.rank_id, body.events, body.marks)
+        .await
+    {
+        Ok(()) => StatusCode::OK,
+        Err(e) => {
+            warn!(user_id = body.user_id, error = %e, "failed to create profile");
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct EventTransactionBody {
+    caller: String,
+    events: Vec<EventJsonBody>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+enum EventResult {
+    Logged,
+    Rejected { reason: String },
+}
+
+/// Looks up a previously-committed transaction for this caller, if the `txn_id` has been seen
+/// before, so a retried PUT after a network timeout returns the original result instead of
+/// re-inserting the events.
+async fn stored_transaction_result(
+    conn: &Connection,
+    caller: &str,
+    txn_id: &str,
+) -> Option<Vec<EventResult>> {
+    let mut rows = conn
+        .query(
+            "SELECT result FROM transactions WHERE caller = ?1 AND txn_id = ?2",
+            (caller, txn_id),
+        )
+        .await
+        .ok()?;
+
+    let row = rows.next().await.ok()??;
+    serde_json::from_str(&row.get::<String>(0).ok()?).ok()
+}
+
+/// PUT /events/transaction/:txn_id — batched, idempotent event ingest. All events in the body
+/// are committed in a single libsql transaction, and the `txn_id` (caller-generated, e.g. a
+/// UUID) is recorded so a replay of the same transaction returns the stored result instead of
+/// double-inserting events and double-awarding marks.
+#[instrument(skip(state, body), fields(txn_id = %txn_id, caller = %body.caller, count = body.events.len()))]
+async fn put_event_transaction(
+    State(state): State<AppState>,
+    Path(txn_id): Path<String>,
+    Json(body): Json<EventTransactionBody>,
+) -> Json<Vec<EventResult>> {
+    let conn = state.db.conn();
+
+    if let Some(stored) = stored_transaction_result(&conn, &body.caller, &txn_id).await {
+        info!("replaying stored transaction result");
+        return Json(stored);
+    }
+
+    let tx = conn.transaction().await.unwrap();
+    let mut results = Vec::with_capacity(body.events.len());
+    let mut logged_events = Vec::new();
+
+    for json_body in body.events {
+        let event = Event::from_json_body(json_body);
+        if event.attendance.len() > state.config.max_attendance {
+            warn!(
+                host = event.host,
+                attendance = event.attendance.len(),
+                max_attendance = state.config.max_attendance,
+                "rejected event in transaction, attendance exceeds max_attendance"
+            );
+            results.push(EventResult::Rejected {
+                reason: format!(
+                    "attendance of {} exceeds max_attendance of {}",
+                    event.attendance.len(),
+                    state.config.max_attendance
+                ),
+            });
+            continue;
+        }
+
+        let attendance_string = match serde_json::to_string(&event.attendance) {
+            Ok(s) => s,
+            Err(e) => {
+                results.push(EventResult::Rejected {
+                    reason: e.to_string(),
+                });
+                continue;
+            }
+        };
+
+        let insert = tx
+            .execute(
+                "INSERT INTO events (host, attendance, event_date, kind, location) VALUES (?1, ?2, ?3, ?4, ?5)",
+                (
+                    event.host,
+                    attendance_string,
+                    event.event_date.to_rfc3339(),
+                    event.kind.as_str(),
+                    event.location.as_str(),
+                ),
+            )
+            .await;
+
+        match insert {
+            Ok(_) => {
+                results.push(EventResult::Logged);
+                logged_events.push(event);
+            }
+            Err(e) => {
+                warn!(host = event.host, error = %e, "rejected event in transaction");
+                results.push(EventResult::Rejected {
+                    reason: e.to_string(),
+                });
+            }
+        }
+    }
+
+    let results_json = serde_json::to_string(&results).unwrap();
+    let inserted = tx
+        .execute(
+            "INSERT OR IGNORE INTO transactions (caller, txn_id, result) VALUES (?1, ?2, ?3)",
+            (body.caller.clone(), txn_id.clone(), results_json),
+        )
         .await
         .unwrap();
-    let conn = db.connect().unwrap();
+
+    if inserted == 0 {
+        // A racing replay of the same txn_id committed first. Discard this attempt's event
+        // inserts rather than double-logging, and return the winner's stored result.
+        tx.rollback().await.unwrap();
+        info!("lost race on concurrent replay, returning winner's stored result");
+
+        let conn = state.db.conn();
+        let stored = stored_transaction_result(&conn, &body.caller, &txn_id)
+            .await
+            .expect("transactions row must exist once INSERT OR IGNORE reports a conflict");
+        return Json(stored);
+    }
+    tx.commit().await.unwrap();
+
+    for event in logged_events {
+        let conn_arc = Arc::new(state.db.conn());
+        event.log_attendance(conn_arc.clone()).await;
+
+        let attendance = event.attendance.clone();
+        webhooks::notify_event(conn_arc.clone(), event);
+        webhooks::check_and_notify_promotions(conn_arc, attendance, state.config.clone()).await;
+    }
+
+    info!(
+        logged = results
+            .iter()
+            .filter(|r| matches!(r, EventResult::Logged))
+            .count(),
+        "committed batched transaction"
+    );
+    Json(results)
+}
+
+#[instrument(skip(state))]
+async fn get_presence(
+    State(state): State<AppState>,
+    Path(user_id): Path<u64>,
+) -> Json<Option<Presence>> {
+    let conn = state.db.conn();
+
+    let mut rows = conn
+        .query(
+            "SELECT user_id, last_online, status FROM presence WHERE user_id = ?1",
+            [user_id],
+        )
+        .await
+        .unwrap();
+
+    let presence = match rows.next().await {
+        Ok(Some(row)) => Some(Presence::from_row(&row)),
+        _ => None,
+    };
+
+    Json(presence)
+}
+
+#[instrument(skip(state, user_ids))]
+async fn get_presence_batch(
+    State(state): State<AppState>,
+    Json(user_ids): Json<Vec<u64>>,
+) -> Json<Vec<Presence>> {
+    let conn = state.db.conn();
+
+    let mut presences = Vec::with_capacity(user_ids.len());
+    for user_id in user_ids {
+        let mut rows = conn
+            .query(
+                "SELECT user_id, last_online, status FROM presence WHERE user_id = ?1",
+                [user_id],
+            )
+            .await
+            .unwrap();
+
+        if let Ok(Some(row)) = rows.next().await {
+            presences.push(Presence::from_row(&row));
+        }
+    }
+
+    Json(presences)
+}
+
+fn presence_status_str(status: PresenceStatus) -> &'static str {
+    match status {
+        PresenceStatus::Online => "online",
+        PresenceStatus::Idle => "idle",
+        PresenceStatus::Offline => "offline",
+    }
+}
+
+/// Polls Roblox presence for every known member and upserts the result into the `presence`
+/// table. Spawned as a background task from `main` on a fixed interval.
+async fn poll_presence_once(db: &database::Database) -> anyhow::Result<()> {
+    let conn = db.conn();
+
+    let mut rows = conn.query("SELECT DISTINCT user_id FROM profiles", ()).await?;
+    let mut user_ids = Vec::new();
+    while let Some(row) = rows.next().await? {
+        user_ids.push(row.get::<u64>(0)?);
+    }
+
+    if user_ids.is_empty() {
+        return Ok(());
+    }
+
+    for chunk in user_ids.chunks(roblox::PRESENCE_BATCH_LIMIT) {
+        let raw_presences = roblox::get_presence_batch(chunk).await?;
+        for (user_id, raw) in raw_presences {
+            let status = Presence::derive_status(raw.last_online, raw.is_online);
+            let last_online_str = raw.last_online.map(|d| d.to_rfc3339());
+
+            conn.execute(
+                "INSERT INTO presence (user_id, last_online, status) VALUES (?1, ?2, ?3)
+                 ON CONFLICT(user_id) DO UPDATE SET last_online = excluded.last_online, status = excluded.status",
+                (user_id, last_online_str, presence_status_str(status)),
+            )
+            .await?;
+        }
+    }
+
+    info!(count = user_ids.len(), "refreshed presence");
+    Ok(())
+}
+
+fn spawn_presence_poller(db: database::Database) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(120));
+        loop {
+            interval.tick().await;
+            if let Err(e) = poll_presence_once(&db).await {
+                warn!(error = %e, "presence poll failed");
+            }
+        }
+    });
+}
+
+// gets the hosted events from the specified userid
+#[instrument(skip(state))]
+async fn get_hosted(State(state): State<AppState>, Path(host_id): Path<u64>) -> Json<Vec<Event>> {
+    info!(host_id, "retrieving hosted events");
+    let conn = state.db.conn();
 
     let mut rows = conn
         .query("SELECT * FROM events WHERE host = ?1", [host_id])
@@ -146,7 +460,7 @@ async fn get_hosted(State(state): State<AppState>, Path(host_id): Path<u64>) ->
         events.push(Event::from_row(&r))
     }
 
-    println!("Successfully retrieved events for {host_id}");
+    info!(host_id, count = events.len(), "retrieved hosted events");
     Json(events)
 }
 
@@ -161,20 +475,70 @@ async fn main() {
     let db_token = util::strip_token(db_token_string);
     let db_url = util::strip_token(db_url_string);
 
+    let otlp_endpoint = secrets_table
+        .get("OTLP_ENDPOINT")
+        .map(|v| util::strip_token(v.to_string()));
+    let _telemetry_guard = telemetry::init(otlp_endpoint.as_deref());
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    let authorized_keys_table = secrets_table
+        .get("AUTHORIZED_KEYS")
+        .and_then(|v| v.as_table())
+        .expect("Secrets.toml must define an [AUTHORIZED_KEYS] table of key_id -> hex pubkey");
+    let authorized_keys_map: std::collections::HashMap<String, String> = authorized_keys_table
+        .iter()
+        .map(|(key_id, pubkey)| (key_id.clone(), util::strip_token(pubkey.to_string())))
+        .collect();
+    let authorized_keys = auth::AuthorizedKeys::from_hex_map(&authorized_keys_map)
+        .expect("AUTHORIZED_KEYS contains an invalid ed25519 public key");
+
+    let config = Arc::new(
+        config::Config::from_secrets(&secrets_table).expect("Secrets.toml has an invalid config"),
+    );
+
+    let db = database::Database::connect(db_url, db_token)
+        .await
+        .expect("failed to connect to database");
+    db.run_migrations()
+        .await
+        .expect("failed to run database migrations");
+
+    spawn_presence_poller(db.clone());
+
     let state = AppState {
-        token: db_token,
-        url: db_url,
+        db,
+        authorized_keys,
+        config: config.clone(),
     };
 
-    let app = Router::new()
+    let protected = Router::new()
+        .route("/events", put(put_event))
+        .route("/events/transaction/:txn_id", put(put_event_transaction))
+        .route("/profiles/increment/:user_id/:increment", post(increment_events))
+        .route("/profiles/create", post(create_profile))
+        .route_layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            auth::verify_signature,
+        ));
+
+    let public = Router::new()
         .route("/profiles/:id", get(get_profile))
         .route("/events/:id", get(get_hosted))
-        .route("/events", put(put_event))
         .route("/events/attended/:id", get(get_events_attended))
         .route("/events/num-attended/:id", get(get_attended))
         .route("/events/info/:id", get(get_event_info_by_info))
+        .route("/presence/:id", get(get_presence))
+        .route("/presence", post(get_presence_batch))
+        .route("/appservices", put(register_webhook));
+
+    let app = public
+        .merge(protected)
+        .layer(axum::middleware::from_fn(telemetry::extract_trace_context))
+        .layer(TraceLayer::new_for_http())
         .with_state(state);
 
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
+    let bind_addr = format!("{}:{}", config.bind_address, config.port);
+    info!(bind_addr, "sol-mainframe listening");
+    let listener = tokio::net::TcpListener::bind(&bind_addr).await.unwrap();
     axum::serve(listener, app).await.unwrap();
 }
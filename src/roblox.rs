@@ -0,0 +1,111 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use reqwest::Client;
+use serde::Deserialize;
+use tracing::instrument;
+
+pub const SOL_GROUP_ID: u64 = 32449365;
+
+#[derive(Debug, Deserialize)]
+struct GroupRole {
+    rank: u32,
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GroupRef {
+    id: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct GroupMembership {
+    group: GroupRef,
+    role: GroupRole,
+}
+
+#[derive(Debug, Deserialize)]
+struct GroupRolesResponse {
+    data: Vec<GroupMembership>,
+}
+
+/// Looks up `user_id`'s rank within `group_id`, returning `(rank_id, rank_name)` if they're a
+/// member or `None` if they're not in the group.
+#[instrument]
+pub async fn get_rank_in_group(group_id: u64, user_id: u64) -> Result<Option<(u64, String)>> {
+    let client = Client::new();
+    let response = client
+        .get(format!(
+            "https://groups.roblox.com/v2/users/{user_id}/groups/roles"
+        ))
+        .send()
+        .await?
+        .json::<GroupRolesResponse>()
+        .await?;
+
+    Ok(response
+        .data
+        .into_iter()
+        .find(|membership| membership.group.id == group_id)
+        .map(|membership| (membership.role.rank as u64, membership.role.name)))
+}
+
+const PRESENCE_URL: &str = "https://presence.roblox.com/v1/presence/users";
+
+/// Roblox's raw `userPresenceType`: `0` offline, `1` on the website, `2` in a game, `3` in Studio.
+#[derive(Debug, Deserialize)]
+struct RawPresence {
+    #[serde(rename = "userId")]
+    user_id: u64,
+    #[serde(rename = "userPresenceType")]
+    presence_type: u8,
+    #[serde(rename = "lastOnline")]
+    last_online: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PresenceBatchResponse {
+    #[serde(rename = "userPresences")]
+    user_presences: Vec<RawPresence>,
+}
+
+/// One user's raw Roblox presence: whether they're currently online (website, in-game, or in
+/// Studio) and the last time Roblox observed them online.
+#[derive(Debug, Clone, Copy)]
+pub struct RawUserPresence {
+    pub is_online: bool,
+    pub last_online: Option<DateTime<Utc>>,
+}
+
+/// Roblox's per-request limit on `userIds` for `/v1/presence/users`. Callers must chunk larger
+/// rosters themselves; a batch over this size gets rejected with a 400.
+pub const PRESENCE_BATCH_LIMIT: usize = 100;
+
+/// Batched presence lookup for up to `PRESENCE_BATCH_LIMIT` user ids. Used to periodically
+/// refresh the `presence` table instead of querying per-member.
+#[instrument]
+pub async fn get_presence_batch(user_ids: &[u64]) -> Result<HashMap<u64, RawUserPresence>> {
+    let client = Client::new();
+    let response = client
+        .post(PRESENCE_URL)
+        .json(&serde_json::json!({ "userIds": user_ids }))
+        .send()
+        .await?
+        .json::<PresenceBatchResponse>()
+        .await?;
+
+    Ok(response
+        .user_presences
+        .into_iter()
+        .map(|p| {
+            (
+                p.user_id,
+                RawUserPresence {
+                    is_online: p.presence_type != 0,
+                    last_online: p.last_online,
+                },
+            )
+        })
+        .collect())
+}
@@ -0,0 +1,90 @@
+use axum::{extract::Request, http::HeaderMap, middleware::Next, response::Response};
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{runtime, trace::Config as TraceConfig};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
+
+/// Handle kept alive for the lifetime of the process so spans are flushed on shutdown.
+pub struct TelemetryGuard {
+    otlp_enabled: bool,
+}
+
+impl Drop for TelemetryGuard {
+    fn drop(&mut self) {
+        if self.otlp_enabled {
+            opentelemetry::global::shutdown_tracer_provider();
+        }
+    }
+}
+
+/// Initializes the global `tracing` subscriber, optionally wiring in an OpenTelemetry OTLP
+/// pipeline when `otlp_endpoint` is configured in `Secrets.toml`. Spans are batch-exported on
+/// a background task and flushed when the returned guard is dropped.
+pub fn init(otlp_endpoint: Option<&str>) -> TelemetryGuard {
+    let env_filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+
+    match otlp_endpoint {
+        Some(endpoint) => {
+            let tracer_provider = opentelemetry_otlp::new_pipeline()
+                .tracing()
+                .with_exporter(
+                    opentelemetry_otlp::new_exporter()
+                        .tonic()
+                        .with_endpoint(endpoint),
+                )
+                .with_trace_config(
+                    TraceConfig::default().with_resource(opentelemetry_sdk::Resource::new(vec![
+                        opentelemetry::KeyValue::new("service.name", "sol-mainframe"),
+                    ])),
+                )
+                .install_batch(runtime::Tokio)
+                .expect("failed to install OTLP tracer pipeline");
+
+            let tracer = tracer_provider.tracer("sol-mainframe");
+            let otel_layer = tracing_opentelemetry::layer().with_tracer(tracer);
+
+            tracing_subscriber::registry()
+                .with(env_filter)
+                .with(tracing_subscriber::fmt::layer())
+                .with(otel_layer)
+                .init();
+
+            TelemetryGuard { otlp_enabled: true }
+        }
+        None => {
+            tracing_subscriber::registry()
+                .with(env_filter)
+                .with(tracing_subscriber::fmt::layer())
+                .init();
+
+            TelemetryGuard {
+                otlp_enabled: false,
+            }
+        }
+    }
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl<'a> opentelemetry::propagation::Extractor for HeaderExtractor<'a> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|v| v.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|k| k.as_str()).collect()
+    }
+}
+
+/// Axum middleware that extracts an inbound `traceparent` header (if present) and attaches it
+/// as the parent context of the current request span, linking a bot-side `log_event` call to
+/// the handler that ultimately performs the DB write.
+pub async fn extract_trace_context(request: Request, next: Next) -> Response {
+    let parent_context = opentelemetry::global::get_text_map_propagator(|propagator| {
+        propagator.extract(&HeaderExtractor(request.headers()))
+    });
+    tracing::Span::current().set_parent(parent_context);
+    next.run(request).await
+}
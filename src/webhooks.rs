@@ -0,0 +1,307 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::Result;
+use libsql::Connection;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use sol_util::mainframe::{Event, Profile};
+use tracing::{instrument, warn};
+
+use crate::config::Config;
+
+/// Consecutive delivery failures after which an endpoint is treated as failing and skipped.
+const MAX_CONSECUTIVE_FAILURES: i32 = 5;
+const RETRY_BACKOFF_BASE: Duration = Duration::from_secs(2);
+const MAX_RETRIES: u32 = 4;
+
+/// A namespace filter scoping which events/promotions a registration receives, mirroring
+/// Matrix application service namespaces.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Namespace {
+    pub kind: Option<String>,
+    pub location: Option<String>,
+    pub min_rank_id: Option<u64>,
+    pub max_rank_id: Option<u64>,
+}
+
+impl Namespace {
+    fn matches_event(&self, event: &Event) -> bool {
+        if let Some(kind) = &self.kind {
+            if kind != &event.kind {
+                return false;
+            }
+        }
+        if let Some(location) = &self.location {
+            if location != &event.location {
+                return false;
+            }
+        }
+        true
+    }
+
+    fn matches_rank(&self, rank_id: u64) -> bool {
+        if let Some(min) = self.min_rank_id {
+            if rank_id < min {
+                return false;
+            }
+        }
+        if let Some(max) = self.max_rank_id {
+            if rank_id > max {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Registration {
+    pub id: i64,
+    pub url: String,
+    pub hs_token: String,
+    pub namespaces: Vec<Namespace>,
+    pub consecutive_failures: i32,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RegisterWebhookBody {
+    pub url: String,
+    pub hs_token: String,
+    pub namespaces: Vec<Namespace>,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(tag = "kind")]
+enum Notification<'a> {
+    Event { event: &'a Event },
+    Promotable { user_ids: &'a [u64] },
+}
+
+/// Persists a new application-service-style webhook registration and returns its id.
+#[instrument(skip(conn, body))]
+pub async fn register(conn: &Connection, body: RegisterWebhookBody) -> Result<i64> {
+    let namespaces_json = serde_json::to_string(&body.namespaces)?;
+    conn.execute(
+        "INSERT INTO appservices (url, hs_token, namespaces, consecutive_failures) VALUES (?1, ?2, ?3, 0)",
+        (body.url, body.hs_token, namespaces_json),
+    )
+    .await?;
+
+    Ok(conn.last_insert_rowid())
+}
+
+async fn all_registrations(conn: &Connection) -> Result<Vec<Registration>> {
+    let mut rows = conn
+        .query(
+            "SELECT id, url, hs_token, namespaces, consecutive_failures FROM appservices",
+            (),
+        )
+        .await?;
+
+    let mut registrations = Vec::new();
+    while let Some(row) = rows.next().await? {
+        let namespaces: Vec<Namespace> = serde_json::from_str(&row.get::<String>(3)?)?;
+        registrations.push(Registration {
+            id: row.get::<i64>(0)?,
+            url: row.get::<String>(1)?,
+            hs_token: row.get::<String>(2)?,
+            namespaces,
+            consecutive_failures: row.get::<i32>(4)?,
+        });
+    }
+
+    Ok(registrations)
+}
+
+async fn record_result(conn: &Connection, id: i64, succeeded: bool) {
+    let query = if succeeded {
+        "UPDATE appservices SET consecutive_failures = 0 WHERE id = ?1"
+    } else {
+        "UPDATE appservices SET consecutive_failures = consecutive_failures + 1 WHERE id = ?1"
+    };
+
+    if let Err(e) = conn.execute(query, [id]).await {
+        warn!(id, error = %e, "failed to record webhook delivery result");
+    }
+}
+
+async fn push_with_retry(client: &Client, registration: &Registration, payload: &Notification<'_>) -> bool {
+    if registration.consecutive_failures >= MAX_CONSECUTIVE_FAILURES {
+        warn!(id = registration.id, url = %registration.url, "skipping webhook endpoint marked as failing");
+        return false;
+    }
+
+    for attempt in 0..=MAX_RETRIES {
+        let result = client
+            .post(&registration.url)
+            .header("Authorization", format!("Bearer {}", registration.hs_token))
+            .json(payload)
+            .send()
+            .await;
+
+        match result {
+            Ok(response) if response.status().is_success() => return true,
+            Ok(response) => {
+                warn!(id = registration.id, status = %response.status(), attempt, "webhook push rejected")
+            }
+            Err(e) => warn!(id = registration.id, error = %e, attempt, "webhook push failed"),
+        }
+
+        if attempt < MAX_RETRIES {
+            tokio::time::sleep(RETRY_BACKOFF_BASE * 2u32.pow(attempt)).await;
+        }
+    }
+
+    false
+}
+
+/// Delivers `payload` to every registration whose namespace matches `matches`, recording a
+/// per-registration delivery result. Takes an already-loaded registration list so a caller
+/// fanning out several payloads in one call only pays for `all_registrations` once.
+async fn push_to_matching(
+    conn: &Connection,
+    client: &Client,
+    registrations: &[Registration],
+    payload: &Notification<'_>,
+    matches: impl Fn(&Namespace) -> bool,
+) {
+    for registration in registrations {
+        if registration.namespaces.iter().any(&matches) {
+            let succeeded = push_with_retry(client, registration, payload).await;
+            record_result(conn, registration.id, succeeded).await;
+        }
+    }
+}
+
+/// Best-effort fan-out of a committed event to every registration whose namespace matches it.
+/// Delivery (including the exponential-backoff retries in `push_with_retry`) runs on a spawned
+/// background task, so a slow or dead subscriber can't stall the caller's HTTP response.
+#[instrument(skip(conn, event))]
+pub fn notify_event(conn: Arc<Connection>, event: Event) {
+    tokio::spawn(async move {
+        let registrations = match all_registrations(&conn).await {
+            Ok(r) => r,
+            Err(e) => {
+                warn!(error = %e, "failed to load appservice registrations");
+                return;
+            }
+        };
+
+        let client = Client::new();
+        let payload = Notification::Event { event: &event };
+        push_to_matching(&conn, &client, &registrations, &payload, |ns| {
+            ns.matches_event(&event)
+        })
+        .await;
+    });
+}
+
+/// Re-reads each attendee's profile after attendance has been logged and, for anyone for whom
+/// `should_promote() == true` now holds (using `config`'s per-rank promotion thresholds rather
+/// than the hardcoded defaults), spawns a background task that loads the registration list once
+/// and notifies every matching subscriber. The profile is not compared with its state before
+/// the event, so a member who was already promotable is notified again on each event they attend.
+#[instrument(skip(conn, user_ids, config))]
+pub async fn check_and_notify_promotions(conn: Arc<Connection>, user_ids: Vec<u64>, config: Arc<Config>) {
+    let mut promotions = Vec::new();
+    for user_id in user_ids {
+        let mut rows = match conn
+            .query("SELECT * FROM profiles WHERE user_id = ?1", [user_id])
+            .await
+        {
+            Ok(r) => r,
+            Err(e) => {
+                warn!(user_id, error = %e, "failed to re-read profile for promotion check");
+                continue;
+            }
+        };
+
+        let row = match rows.next().await {
+            Ok(Some(row)) => row,
+            _ => continue,
+        };
+
+        let profile = Profile::from_row(&row);
+        if profile.should_promote(config.required_marks_for(profile.rank_id)) {
+            promotions.push((profile.rank_id, profile.user_id));
+        }
+    }
+
+    if promotions.is_empty() {
+        return;
+    }
+
+    tokio::spawn(async move {
+        let registrations = match all_registrations(&conn).await {
+            Ok(r) => r,
+            Err(e) => {
+                warn!(error = %e, "failed to load appservice registrations");
+                return;
+            }
+        };
+
+        let client = Client::new();
+        for (rank_id, user_id) in promotions {
+            let payload = Notification::Promotable {
+                user_ids: &[user_id],
+            };
+            push_to_matching(&conn, &client, &registrations, &payload, |ns| {
+                ns.matches_rank(rank_id)
+            })
+            .await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(kind: &str, location: &str) -> Event {
+        Event::new(1, vec![1], location.to_string(), kind.to_string())
+    }
+
+    fn namespace(kind: Option<&str>, location: Option<&str>) -> Namespace {
+        Namespace {
+            kind: kind.map(String::from),
+            location: location.map(String::from),
+            min_rank_id: None,
+            max_rank_id: None,
+        }
+    }
+
+    #[test]
+    fn matches_event_requires_every_set_filter_to_match() {
+        let ns = namespace(Some("raid"), Some("hq"));
+        assert!(ns.matches_event(&event("raid", "hq")));
+        assert!(!ns.matches_event(&event("raid", "outpost")));
+        assert!(!ns.matches_event(&event("drill", "hq")));
+    }
+
+    #[test]
+    fn matches_event_with_no_filters_matches_everything() {
+        let ns = namespace(None, None);
+        assert!(ns.matches_event(&event("anything", "anywhere")));
+    }
+
+    #[test]
+    fn matches_rank_respects_min_and_max_bounds() {
+        let ns = Namespace {
+            kind: None,
+            location: None,
+            min_rank_id: Some(2),
+            max_rank_id: Some(4),
+        };
+        assert!(!ns.matches_rank(1));
+        assert!(ns.matches_rank(2));
+        assert!(ns.matches_rank(4));
+        assert!(!ns.matches_rank(5));
+    }
+
+    #[test]
+    fn matches_rank_with_no_bounds_matches_everything() {
+        let ns = namespace(None, None);
+        assert!(ns.matches_rank(0));
+        assert!(ns.matches_rank(u64::MAX));
+    }
+}
@@ -1,15 +1,112 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    sync::OnceLock,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 use anyhow::Result;
+use base64::Engine;
 use chrono::{DateTime, Datelike, Utc, Weekday};
+use ed25519_dalek::{Signer, SigningKey};
 use libsql::Row;
-use reqwest::Client;
+use opentelemetry::propagation::Injector;
+use reqwest::{Client, RequestBuilder};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::instrument;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use uuid::Uuid;
 
-use crate::rank::{self, Rank};
 
 const MAINFRAME_URL: &str = "http://localhost:3000";
 
+/// Identifies this client's key to the mainframe's authorized key registry.
+const SIGNING_KEY_ID: &str = "sol-bot";
+
+fn signing_key() -> &'static SigningKey {
+    static KEY: OnceLock<SigningKey> = OnceLock::new();
+    KEY.get_or_init(|| {
+        let hex_seed = std::env::var("SOL_SIGNING_KEY").expect("SOL_SIGNING_KEY must be set");
+        let seed: [u8; 32] = hex::decode(hex_seed)
+            .expect("SOL_SIGNING_KEY must be hex-encoded")
+            .try_into()
+            .expect("SOL_SIGNING_KEY must decode to 32 bytes");
+        SigningKey::from_bytes(&seed)
+    })
+}
+
+/// Recursively sorts JSON object keys; mirrors the mainframe's `auth::canonicalize` so both
+/// sides hash identical bytes regardless of field order.
+fn canonicalize(value: &Value) -> Value {
+    match value {
+        Value::Object(map) => {
+            let mut keys: Vec<&String> = map.keys().collect();
+            keys.sort();
+            let sorted = keys
+                .into_iter()
+                .map(|k| (k.clone(), canonicalize(&map[k])))
+                .collect();
+            Value::Object(sorted)
+        }
+        Value::Array(items) => Value::Array(items.iter().map(canonicalize).collect()),
+        other => other.clone(),
+    }
+}
+
+fn sign_canonical(method: &str, path: &str, canonical_body: &str) -> (String, String) {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+        .to_string();
+
+    let canonical = format!("{method}\n{path}\n{timestamp}\n{canonical_body}");
+    let signature = signing_key().sign(canonical.as_bytes());
+    let header = format!(
+        "{SIGNING_KEY_ID}:{}",
+        base64::engine::general_purpose::STANDARD.encode(signature.to_bytes())
+    );
+
+    (timestamp, header)
+}
+
+/// Signs a JSON request body and returns the `(X-Timestamp, X-Signature)` header values,
+/// mirroring the mainframe's `auth::verify_signature` middleware.
+fn sign_request(method: &str, path: &str, body: &impl Serialize) -> (String, String) {
+    let value = serde_json::to_value(body).unwrap();
+    let canonical_body = serde_json::to_string(&canonicalize(&value)).unwrap();
+    sign_canonical(method, path, &canonical_body)
+}
+
+/// Signs a bodyless request (e.g. a plain POST with no JSON payload).
+fn sign_empty_request(method: &str, path: &str) -> (String, String) {
+    sign_canonical(method, path, "")
+}
+
+struct HeaderInjector<'a>(&'a mut reqwest::header::HeaderMap);
+
+impl<'a> Injector for HeaderInjector<'a> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(name), Ok(val)) = (
+            reqwest::header::HeaderName::from_bytes(key.as_bytes()),
+            reqwest::header::HeaderValue::from_str(&value),
+        ) {
+            self.0.insert(name, val);
+        }
+    }
+}
+
+/// Injects the current span's `traceparent` into an outbound request so the mainframe can link
+/// its handler span back to this client call.
+fn with_trace_context(request: RequestBuilder) -> RequestBuilder {
+    let context = tracing::Span::current().context();
+    let mut headers = reqwest::header::HeaderMap::new();
+    opentelemetry::global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut HeaderInjector(&mut headers))
+    });
+    request.headers(headers)
+}
+
 #[derive(Clone, Deserialize, Serialize, Debug)]
 pub struct Event {
     pub host: u64,
@@ -131,17 +228,23 @@ impl Profile {
         }
     }
 
-    pub fn should_promote(&self) -> bool {
-        let rank = Rank::from_rank_id(self.rank_id).unwrap();
-        if let Some(marks) = rank.required_marks() {
-            return self.marks_at_current_rank == marks;
+    /// Returns true if this profile's marks at its current rank equal exactly the number needed to
+    /// promote out of it; a count above the threshold returns false. `required_marks` is the caller-supplied threshold for `self.rank_id` — typically
+    /// `Config::required_marks_for(self.rank_id)` so a deployment can override per-rank
+    /// requirements without recompiling. `None` (an unconfigured or terminal rank) never
+    /// promotes.
+    pub fn should_promote(&self, required_marks: Option<i32>) -> bool {
+        match required_marks {
+            Some(marks) => self.marks_at_current_rank == marks,
+            None => false,
         }
-
-        false
     }
 
-    pub fn try_award_mark(&mut self) -> bool {
-        if self.events_attended_this_week == rank::EVENT_PER_WEEK_FOR_MARK {
+    /// Tries to award a mark for hitting the weekly event quota. `events_per_week_for_mark` is
+    /// the caller-supplied quota — typically `Config::events_per_week_for_mark` so a deployment
+    /// can override it without recompiling.
+    pub fn try_award_mark(&mut self, events_per_week_for_mark: i32) -> bool {
+        if self.events_attended_this_week == events_per_week_for_mark {
             self.total_marks += 1;
             self.marks_at_current_rank += 1;
 
@@ -175,10 +278,10 @@ impl Profile {
     }
 }
 
+#[instrument]
 pub async fn get_profile(user_id: u64) -> Result<Profile> {
     let client = Client::new();
-    let response = client
-        .get(format!("{MAINFRAME_URL}/profiles/{user_id}"))
+    let response = with_trace_context(client.get(format!("{MAINFRAME_URL}/profiles/{user_id}")))
         .send()
         .await?;
 
@@ -187,34 +290,38 @@ pub async fn get_profile(user_id: u64) -> Result<Profile> {
     Ok(profile)
 }
 
+#[instrument]
 pub async fn get_num_attendance(user_id: u64) -> Result<u64> {
     let client = Client::new();
-    let response = client
-        .get(format!("{MAINFRAME_URL}/events/num-attended/{user_id}"))
-        .send()
-        .await?;
+    let response = with_trace_context(client.get(format!(
+        "{MAINFRAME_URL}/events/num-attended/{user_id}"
+    )))
+    .send()
+    .await?;
 
     let count = response.json::<u64>().await?;
 
     Ok(count)
 }
 
+#[instrument]
 pub async fn get_events_attended(user_id: u64) -> Result<Vec<u64>> {
     let client = Client::new();
-    let response = client
-        .get(format!("{MAINFRAME_URL}/events/attended/{user_id}"))
-        .send()
-        .await?;
+    let response = with_trace_context(client.get(format!(
+        "{MAINFRAME_URL}/events/attended/{user_id}"
+    )))
+    .send()
+    .await?;
 
     let events = response.json::<Vec<u64>>().await?;
 
     Ok(events)
 }
 
+#[instrument]
 pub async fn get_event(event_id: u64) -> Result<Event> {
     let client = Client::new();
-    let response = client
-        .get(format!("{MAINFRAME_URL}/events/info/{event_id}"))
+    let response = with_trace_context(client.get(format!("{MAINFRAME_URL}/events/info/{event_id}")))
         .send()
         .await?;
 
@@ -223,41 +330,85 @@ pub async fn get_event(event_id: u64) -> Result<Event> {
     Ok(event)
 }
 
+/// Identifies this client to the mainframe's transaction log, so a replayed `txn_id` is scoped
+/// to the caller that originally sent it.
+const CALLER_ID: &str = "sol-bot";
+
+#[derive(Serialize)]
+struct EventTransactionBody {
+    caller: String,
+    events: Vec<EventJsonBody>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+enum EventResult {
+    Logged,
+    Rejected { reason: String },
+}
+
+/// Logs a single event through the batched transaction endpoint. `txn_id` should be a
+/// caller-generated UUID that stays the same across retries of the *same* logical attempt
+/// (e.g. after a timeout) so the mainframe can recognize the replay and avoid double-awarding
+/// marks; generate a fresh UUID for each genuinely new event.
 // host's roblox user id, list of roblox usernames
+#[instrument(fields(host, location = %location, kind = %kind, txn_id = %txn_id))]
 pub async fn log_event(
     host: u64,
     attendees: Vec<String>,
     location: String,
     kind: String,
+    txn_id: Uuid,
 ) -> Result<()> {
-    let body = EventJsonBody {
-        host,
-        names: attendees,
-        location,
-        kind,
-        metadata: None,
+    let body = EventTransactionBody {
+        caller: CALLER_ID.to_string(),
+        events: vec![EventJsonBody {
+            host,
+            names: attendees,
+            location,
+            kind,
+            metadata: None,
+        }],
     };
 
-    println!("Sending LogEvent {body:?}");
+    tracing::info!(events = ?body.events, "sending LogEvent");
+
+    let path = format!("/events/transaction/{txn_id}");
+    let (timestamp, signature) = sign_request("PUT", &path, &body);
 
     let client = Client::new();
-    client
-        .put(format!("{MAINFRAME_URL}/events"))
-        .json(&body)
-        .send()
-        .await?;
+    let response = with_trace_context(
+        client
+            .put(format!("{MAINFRAME_URL}{path}"))
+            .header("X-Timestamp", timestamp)
+            .header("X-Signature", signature)
+            .json(&body),
+    )
+    .send()
+    .await?;
+
+    let results = response.json::<Vec<EventResult>>().await?;
+    if let Some(EventResult::Rejected { reason }) = results.into_iter().next() {
+        anyhow::bail!("event rejected by mainframe: {reason}");
+    }
 
     Ok(())
 }
 
+#[instrument]
 pub async fn increment_events(user_id: u64, increment: i32) -> Result<()> {
+    let path = format!("/profiles/increment/{user_id}/{increment}");
+    let (timestamp, signature) = sign_empty_request("POST", &path);
+
     let client = Client::new();
-    client
-        .post(format!(
-            "{MAINFRAME_URL}/profiles/increment/{user_id}/{increment}"
-        ))
-        .send()
-        .await?;
+    with_trace_context(
+        client
+            .post(format!("{MAINFRAME_URL}{path}"))
+            .header("X-Timestamp", timestamp)
+            .header("X-Signature", signature),
+    )
+    .send()
+    .await?;
 
     Ok(())
 }
@@ -271,6 +422,7 @@ pub struct CreateProfileBody {
     pub marks: i32,
 }
 
+#[instrument]
 pub async fn create_profile(
     user_id: u64,
     username: String,
@@ -285,19 +437,25 @@ pub async fn create_profile(
         events,
         marks,
     };
+    let (timestamp, signature) = sign_request("POST", "/profiles/create", &body);
+
     let client = Client::new();
-    let _ = client
-        .post(format!("{MAINFRAME_URL}/profiles/create"))
-        .json(&body)
-        .send()
-        .await?;
+    let _ = with_trace_context(
+        client
+            .post(format!("{MAINFRAME_URL}/profiles/create"))
+            .header("X-Timestamp", timestamp)
+            .header("X-Signature", signature)
+            .json(&body),
+    )
+    .send()
+    .await?;
     Ok(())
 }
 
+#[instrument]
 pub async fn get_promotable() -> Result<Vec<u64>> {
     let client = Client::new();
-    let response = client
-        .get(format!("{MAINFRAME_URL}/profiles/promotable"))
+    let response = with_trace_context(client.get(format!("{MAINFRAME_URL}/profiles/promotable")))
         .send()
         .await?;
 
@@ -305,6 +463,75 @@ pub async fn get_promotable() -> Result<Vec<u64>> {
     Ok(users)
 }
 
+/// How long after `last_online` a member whom Roblox no longer reports as online still counts as
+/// `Idle`; past this they are `Offline`. `Online` depends only on Roblox's current report.
+const IDLE_THRESHOLD_MINUTES: i64 = 30;
+
+#[derive(Clone, Copy, Deserialize, Serialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PresenceStatus {
+    Online,
+    Idle,
+    Offline,
+}
+
+/// A member's Roblox presence, combined with `Profile::last_event_attended_date` by callers to
+/// flag members who are frequently online yet not attending events.
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub struct Presence {
+    pub user_id: u64,
+    pub last_online: Option<DateTime<Utc>>,
+    pub status: PresenceStatus,
+}
+
+impl Presence {
+    /// Derives a status from whether Roblox reports the member as currently online and, if
+    /// not, how stale the last observed `last_online` is.
+    pub fn derive_status(last_online: Option<DateTime<Utc>>, currently_online: bool) -> PresenceStatus {
+        if currently_online {
+            return PresenceStatus::Online;
+        }
+
+        match last_online {
+            Some(last) if Utc::now() - last < chrono::Duration::minutes(IDLE_THRESHOLD_MINUTES) => {
+                PresenceStatus::Idle
+            }
+            _ => PresenceStatus::Offline,
+        }
+    }
+
+    pub fn from_row(row: &Row) -> Self {
+        let user_id = row.get::<u64>(0).unwrap();
+        let last_online = row
+            .get::<Option<String>>(1)
+            .unwrap()
+            .map(|s| chrono::DateTime::parse_from_rfc3339(&s).unwrap().to_utc());
+        let status = match row.get::<String>(2).unwrap().as_str() {
+            "online" => PresenceStatus::Online,
+            "idle" => PresenceStatus::Idle,
+            _ => PresenceStatus::Offline,
+        };
+
+        Self {
+            user_id,
+            last_online,
+            status,
+        }
+    }
+}
+
+/// Fetches presence for a batch of members from the mainframe's `presence` table.
+#[instrument]
+pub async fn presence_for(user_ids: Vec<u64>) -> Result<Vec<Presence>> {
+    let client = Client::new();
+    let response = with_trace_context(client.post(format!("{MAINFRAME_URL}/presence")).json(&user_ids))
+        .send()
+        .await?;
+
+    let presences = response.json::<Vec<Presence>>().await?;
+    Ok(presences)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -318,4 +545,38 @@ mod tests {
         assert!(has_date_rolled_over(t1));
         assert!(!has_date_rolled_over(t2));
     }
+
+    #[test]
+    fn derive_status_online_when_roblox_reports_online() {
+        assert_eq!(
+            Presence::derive_status(None, true),
+            PresenceStatus::Online
+        );
+    }
+
+    #[test]
+    fn derive_status_idle_within_threshold() {
+        let last_online = Utc::now() - Duration::minutes(IDLE_THRESHOLD_MINUTES - 1);
+        assert_eq!(
+            Presence::derive_status(Some(last_online), false),
+            PresenceStatus::Idle
+        );
+    }
+
+    #[test]
+    fn derive_status_offline_past_threshold() {
+        let last_online = Utc::now() - Duration::minutes(IDLE_THRESHOLD_MINUTES + 1);
+        assert_eq!(
+            Presence::derive_status(Some(last_online), false),
+            PresenceStatus::Offline
+        );
+    }
+
+    #[test]
+    fn derive_status_offline_when_never_seen() {
+        assert_eq!(
+            Presence::derive_status(None, false),
+            PresenceStatus::Offline
+        );
+    }
 }
\ No newline at end of file
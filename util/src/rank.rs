@@ -0,0 +1,37 @@
+/// Default number of events a member must attend in a calendar week before a mark is awarded.
+/// This is only the fallback value baked into the binary; deployments override it via
+/// `Secrets.toml`'s `EVENTS_PER_WEEK_FOR_MARK`, which callers thread into
+/// `Profile::try_award_mark` as `Config::events_per_week_for_mark`.
+pub const EVENT_PER_WEEK_FOR_MARK: i32 = 3;
+
+/// Default marks required to promote out of each rank, keyed by Roblox group rank id. `None`
+/// marks a terminal rank with no further promotion. This is only the fallback table baked into
+/// the binary; deployments override it per-rank via `Secrets.toml`'s `[REQUIRED_MARKS]` table,
+/// which callers thread into `Profile::should_promote` as `Config::required_marks_for`.
+const REQUIRED_MARKS: &[(u64, Option<i32>)] = &[(1, Some(3)), (2, Some(5)), (3, Some(8)), (4, None)];
+
+/// A SOL group rank, identified by its Roblox group rank id.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rank {
+    id: u64,
+}
+
+impl Rank {
+    pub fn from_rank_id(rank_id: u64) -> Option<Self> {
+        REQUIRED_MARKS
+            .iter()
+            .any(|(id, _)| *id == rank_id)
+            .then_some(Self { id: rank_id })
+    }
+
+    pub fn rank_id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn required_marks(&self) -> Option<i32> {
+        REQUIRED_MARKS
+            .iter()
+            .find(|(id, _)| *id == self.id)
+            .and_then(|(_, marks)| *marks)
+    }
+}